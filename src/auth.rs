@@ -0,0 +1,71 @@
+//! Two-factor challenge/response: a primary-credential check that may either
+//! accept outright or hand back a [`Challenge`] nonce, followed by an
+//! HMAC-SHA256 second factor over a shared secret. `no_std`-friendly so the
+//! same logic runs on firmware, not just a host example.
+
+use serde::{Deserialize, Serialize};
+
+use crate::sha256::hmac_sha256;
+pub use crate::sha256::constant_time_eq;
+
+/// Length in bytes of the random nonce issued as a [`Challenge`].
+pub const CHALLENGE_LEN: usize = 16;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Token(pub u64);
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Challenge([u8; CHALLENGE_LEN]);
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
+pub struct Response(pub [u8; 32]);
+#[derive(Debug)]
+pub struct Error;
+
+impl Challenge {
+    /// Wrap a nonce from a caller-supplied random source. `no_std` callers
+    /// (no `rand`/OS entropy available) use this with their own RNG.
+    pub fn from_bytes(nonce: [u8; CHALLENGE_LEN]) -> Self {
+        Self(nonce)
+    }
+
+    /// Draw a fresh random nonce for this session's second factor.
+    #[cfg(feature = "std")]
+    pub fn random() -> Self {
+        use rand::RngCore;
+        let mut nonce = [0u8; CHALLENGE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        Self(nonce)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Offer {
+    Authenticated(Token),
+    Challenge(Challenge),
+}
+
+pub trait Auther {
+    async fn check_creds(&mut self) -> Result<Offer, Error>;
+    async fn challenge_response(&mut self, challenge: &Challenge) -> Result<Token, Error>;
+    async fn abort(&mut self);
+}
+
+pub async fn two_factor<TM: Auther>(tm: &mut TM) -> Result<Token, Error> {
+    let result = async {
+        let outcome = tm.check_creds().await?;
+        match outcome {
+            Offer::Authenticated(token) => Ok(token),
+            Offer::Challenge(challenge) => tm.challenge_response(&challenge).await,
+        }
+    }
+    .await;
+    if result.is_err() {
+        tm.abort().await;
+    }
+    result
+}
+
+/// The second-factor response to a [`Challenge`], given the shared secret
+/// both sides hold.
+pub fn challenge_responder(shared_secret: &[u8], c: &Challenge) -> Response {
+    Response(hmac_sha256(shared_secret, &c.0))
+}
@@ -0,0 +1,90 @@
+//! Runtime-agnostic async channel abstraction.
+//!
+//! [`BlComms`](crate::BlComms) is implemented generically for anything that
+//! implements [`AsyncChannel`], so the bootloader state machine itself never
+//! names `tokio` or any other executor. A `std` host (or test harness) wires
+//! up [`tokio_channel::TokioChannel`]; firmware on an MCU wires up
+//! [`embedded_channel::EmbassyChannel`] instead, and `Bootloader` runs
+//! unchanged on either.
+
+/// A bounded half-duplex mailbox: send a `Tx`, receive an `Rx`.
+pub trait AsyncChannel {
+    type Tx;
+    type Rx;
+    type Error;
+
+    async fn send(&mut self, item: Self::Tx) -> Result<(), Self::Error>;
+    async fn recv(&mut self) -> Result<Self::Rx, Self::Error>;
+}
+
+#[cfg(feature = "std")]
+pub mod tokio_channel {
+    use super::AsyncChannel;
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    /// `tokio::sync::mpsc`-backed channel: send on one direction's `Sender`,
+    /// receive on the other direction's `Receiver`.
+    pub struct TokioChannel<Tx, Rx> {
+        tx: Sender<Tx>,
+        rx: Receiver<Rx>,
+    }
+
+    impl<Tx, Rx> TokioChannel<Tx, Rx> {
+        pub fn new(tx: Sender<Tx>, rx: Receiver<Rx>) -> Self {
+            Self { tx, rx }
+        }
+    }
+
+    impl<Tx: 'static, Rx: 'static> AsyncChannel for TokioChannel<Tx, Rx> {
+        type Tx = Tx;
+        type Rx = Rx;
+        type Error = ();
+
+        async fn send(&mut self, item: Tx) -> Result<(), ()> {
+            self.tx.send(item).await.map_err(drop)
+        }
+
+        async fn recv(&mut self) -> Result<Rx, ()> {
+            self.rx.recv().await.ok_or(())
+        }
+    }
+}
+
+#[cfg(feature = "embedded")]
+pub mod embedded_channel {
+    use super::AsyncChannel;
+    use embassy_sync::blocking_mutex::raw::RawMutex;
+    use embassy_sync::channel::Channel;
+
+    /// Bounded `embassy-sync` channel pair, for firmware builds with a
+    /// fixed-capacity queue instead of an allocator-backed `mpsc`.
+    pub struct EmbassyChannel<'a, M: RawMutex, Tx, Rx, const TX_N: usize, const RX_N: usize> {
+        tx: &'a Channel<M, Tx, TX_N>,
+        rx: &'a Channel<M, Rx, RX_N>,
+    }
+
+    impl<'a, M: RawMutex, Tx, Rx, const TX_N: usize, const RX_N: usize>
+        EmbassyChannel<'a, M, Tx, Rx, TX_N, RX_N>
+    {
+        pub fn new(tx: &'a Channel<M, Tx, TX_N>, rx: &'a Channel<M, Rx, RX_N>) -> Self {
+            Self { tx, rx }
+        }
+    }
+
+    impl<'a, M: RawMutex, Tx, Rx, const TX_N: usize, const RX_N: usize> AsyncChannel
+        for EmbassyChannel<'a, M, Tx, Rx, TX_N, RX_N>
+    {
+        type Tx = Tx;
+        type Rx = Rx;
+        type Error = ();
+
+        async fn send(&mut self, item: Tx) -> Result<(), ()> {
+            self.tx.send(item).await;
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> Result<Rx, ()> {
+            Ok(self.rx.receive().await)
+        }
+    }
+}